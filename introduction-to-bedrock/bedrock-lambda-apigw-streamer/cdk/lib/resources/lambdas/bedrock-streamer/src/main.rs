@@ -1,17 +1,29 @@
 use aws_config::{meta::region::RegionProviderChain, BehaviorVersion};
 use aws_lambda_events::apigw::ApiGatewayWebsocketProxyRequest;
 use aws_sdk_apigatewaymanagement::{
-    config::Builder, primitives::Blob as ApiGatewayBlob, Client as ApiGatewayManagementClient,
+    config::Builder, error::SdkError, operation::post_to_connection::PostToConnectionError,
+    primitives::Blob as ApiGatewayBlob, Client as ApiGatewayManagementClient,
+};
+use aws_sdk_bedrockagentruntime::{
+    operation::invoke_agent::InvokeAgentOutput, types::ResponseStream as AgentResponseStream,
+    Client as BedrockAgentRuntimeClient,
 };
 use aws_sdk_bedrockruntime::{
     operation::invoke_model_with_response_stream::InvokeModelWithResponseStreamOutput,
     primitives::Blob as BedrockBlob, types::ResponseStream, Client as BedrockClient,
 };
+use aws_sdk_dynamodb::{types::AttributeValue, Client as DynamoDbClient};
 use http::Uri;
 use lambda_runtime::{service_fn, Error as LambdaError, LambdaEvent};
+use opentelemetry::{global, metrics::Counter, metrics::Histogram, KeyValue};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, Layer};
 
 /// Required Amazon API Gateway response
 #[derive(serde::Serialize)]
@@ -21,21 +33,331 @@ struct ApiGatewayResponse {
     body: String,
 }
 
-/// Response for each stream record sent from Amazon Bedrock
-#[derive(Debug, Deserialize, Serialize)]
-struct BedrockResponse {
-    #[serde(rename = "type")]
-    response_type: String,
-    completion: String,
-    stop_reason: Option<String>,
-    stop: Option<Value>,
+/// Frame forwarded to the WebSocket client — either a piece of generated text or, for a RAG
+/// query, the citations backing the answer. Tagged with the client's `requestId` so the browser
+/// can route frames from multiple in-flight generations on the same connection.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum OutboundFrame {
+    Completion {
+        #[serde(rename = "requestId")]
+        request_id: String,
+        completion: String,
+    },
+    Citations {
+        #[serde(rename = "requestId")]
+        request_id: String,
+        citations: Vec<Citation>,
+    },
+}
+
+/// A single retrieved reference backing a Bedrock Agent / Knowledge Base answer
+#[derive(Debug, Serialize)]
+struct Citation {
+    content: Option<String>,
+    location: Option<String>,
+}
+
+/// A `$default` payload is either a stop command, a free-form story prompt, or a
+/// retrieval-augmented query against a Bedrock Agent / Knowledge Base. Untagged so each variant
+/// is picked by whichever required fields the payload actually has.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ClientRequest {
+    Stop(StopRequest),
+    RagQuery(RagRequest),
+    Story(StoryRequest),
+}
+
+/// Cancel one multiplexed generation on this connection
+#[derive(Debug, Deserialize)]
+struct StopRequest {
+    action: String,
+    #[serde(rename = "requestId")]
+    request_id: String,
+}
+
+/// Retrieval-augmented query against a Bedrock Agent
+#[derive(Debug, Deserialize)]
+struct RagRequest {
+    #[serde(rename = "requestId")]
+    request_id: String,
+    #[serde(rename = "agentId")]
+    agent_id: String,
+    #[serde(rename = "agentAliasId")]
+    agent_alias_id: String,
+    query: String,
 }
 
 /// Bedrock story
 #[derive(Debug, Deserialize)]
 struct StoryRequest {
+    #[serde(rename = "requestId")]
+    request_id: String,
     #[serde(rename = "storyType")]
     story_type: String,
+    #[serde(rename = "modelId", default = "default_model_id")]
+    model_id: String,
+}
+
+fn default_model_id() -> String {
+    "anthropic.claude-v2".to_string()
+}
+
+/// Generation parameters shared across adapters; not every adapter uses every field.
+struct ModelParams {
+    temperature: f64,
+    max_tokens: u32,
+    top_p: f64,
+    top_k: u32,
+}
+
+impl Default for ModelParams {
+    fn default() -> Self {
+        ModelParams {
+            temperature: 0.7,
+            max_tokens: 300,
+            top_p: 1.0,
+            top_k: 250,
+        }
+    }
+}
+
+/// Result of parsing a single chunk of a model's response stream
+#[derive(Debug)]
+enum StreamEvent {
+    /// An incremental piece of generated text to forward to the client
+    Delta(String),
+    /// The model finished generating; carries the final stop reason and token usage
+    Done {
+        stop_reason: Option<String>,
+        input_tokens: Option<u32>,
+        output_tokens: Option<u32>,
+    },
+    /// Nothing worth forwarding (e.g. a Claude 3 `message_start`/`content_block_start` event)
+    Ignored,
+}
+
+/// Builds the model-specific request body and parses the model-specific response stream so
+/// `handle_default` isn't hard-wired to a single model family.
+trait ModelAdapter: Send + Sync {
+    /// Wrap the prompt in this model's expected format and build the full Bedrock request body.
+    fn build_body(&self, prompt: &str, params: &ModelParams) -> Value;
+
+    /// Parse a single chunk of the response stream. Takes `&mut self` because some adapters
+    /// (e.g. Claude 3) need to carry state, such as input token usage, across chunks.
+    fn parse_event(&mut self, chunk: &Value) -> StreamEvent;
+}
+
+/// Claude text-completion models (e.g. `anthropic.claude-v2`)
+struct ClaudeTextAdapter;
+
+impl ModelAdapter for ClaudeTextAdapter {
+    fn build_body(&self, prompt: &str, params: &ModelParams) -> Value {
+        let wrapped_prompt = format!("\n\nHuman: {}\n\nAssistant:", prompt);
+        json!({
+            "prompt": wrapped_prompt,
+            "max_tokens_to_sample": params.max_tokens,
+            "temperature": params.temperature,
+            "top_k": params.top_k,
+            "top_p": params.top_p,
+            "stop_sequences": ["\n\nHuman:"]
+        })
+    }
+
+    fn parse_event(&mut self, chunk: &Value) -> StreamEvent {
+        if let Some(text) = chunk.get("completion").and_then(Value::as_str) {
+            if !text.is_empty() {
+                return StreamEvent::Delta(text.to_string());
+            }
+        }
+        match chunk.get("stop_reason").and_then(Value::as_str) {
+            Some(stop_reason) => StreamEvent::Done {
+                stop_reason: Some(stop_reason.to_string()),
+                input_tokens: None,
+                output_tokens: None,
+            },
+            None => StreamEvent::Ignored,
+        }
+    }
+}
+
+/// Claude 3 Messages API models (e.g. `anthropic.claude-3-sonnet-20240229-v1:0`)
+#[derive(Default)]
+struct ClaudeMessagesAdapter {
+    input_tokens: Option<u32>,
+}
+
+impl ModelAdapter for ClaudeMessagesAdapter {
+    fn build_body(&self, prompt: &str, params: &ModelParams) -> Value {
+        json!({
+            "anthropic_version": "bedrock-2023-05-31",
+            "max_tokens": params.max_tokens,
+            "temperature": params.temperature,
+            "top_p": params.top_p,
+            "top_k": params.top_k,
+            "messages": [
+                { "role": "user", "content": prompt }
+            ]
+        })
+    }
+
+    fn parse_event(&mut self, chunk: &Value) -> StreamEvent {
+        let event: ClaudeMessageEvent = match serde_json::from_value(chunk.clone()) {
+            Ok(event) => event,
+            Err(_) => return StreamEvent::Ignored,
+        };
+
+        match event {
+            ClaudeMessageEvent::MessageStart { message } => {
+                self.input_tokens = message.usage.input_tokens;
+                StreamEvent::Ignored
+            }
+            ClaudeMessageEvent::ContentBlockDelta { delta } => match delta.text {
+                Some(text) if !text.is_empty() => StreamEvent::Delta(text),
+                _ => StreamEvent::Ignored,
+            },
+            ClaudeMessageEvent::MessageDelta { delta, usage } => StreamEvent::Done {
+                stop_reason: delta.stop_reason,
+                input_tokens: self.input_tokens,
+                output_tokens: usage.output_tokens,
+            },
+            ClaudeMessageEvent::ContentBlockStart {}
+            | ClaudeMessageEvent::ContentBlockStop {}
+            | ClaudeMessageEvent::MessageStop {} => StreamEvent::Ignored,
+        }
+    }
+}
+
+/// Claude 3 Messages API stream events, tagged on `type`
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type")]
+enum ClaudeMessageEvent {
+    #[serde(rename = "message_start")]
+    MessageStart { message: ClaudeMessageStart },
+    #[serde(rename = "content_block_start")]
+    ContentBlockStart {},
+    #[serde(rename = "content_block_delta")]
+    ContentBlockDelta { delta: ClaudeContentDelta },
+    #[serde(rename = "content_block_stop")]
+    ContentBlockStop {},
+    #[serde(rename = "message_delta")]
+    MessageDelta {
+        delta: ClaudeMessageDelta,
+        usage: ClaudeUsage,
+    },
+    #[serde(rename = "message_stop")]
+    MessageStop {},
+}
+
+#[derive(Debug, Deserialize)]
+struct ClaudeMessageStart {
+    usage: ClaudeUsage,
+}
+
+#[derive(Debug, Deserialize)]
+struct ClaudeContentDelta {
+    text: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ClaudeMessageDelta {
+    stop_reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ClaudeUsage {
+    input_tokens: Option<u32>,
+    output_tokens: Option<u32>,
+}
+
+/// Llama 3 models (e.g. `meta.llama3-8b-instruct-v1:0`)
+struct Llama3Adapter;
+
+impl ModelAdapter for Llama3Adapter {
+    fn build_body(&self, prompt: &str, params: &ModelParams) -> Value {
+        let wrapped_prompt = format!(
+            "<|begin_of_text|><|start_header_id|>user<|end_header_id|>\n{}<|eot_id|><|start_header_id|>assistant<|end_header_id|>\n",
+            prompt
+        );
+        json!({
+            "prompt": wrapped_prompt,
+            "max_gen_len": params.max_tokens,
+            "temperature": params.temperature,
+            "top_p": params.top_p
+        })
+    }
+
+    fn parse_event(&mut self, chunk: &Value) -> StreamEvent {
+        if let Some(text) = chunk.get("generation").and_then(Value::as_str) {
+            if !text.is_empty() {
+                return StreamEvent::Delta(text.to_string());
+            }
+        }
+        match chunk.get("stop_reason").and_then(Value::as_str) {
+            Some(stop_reason) => StreamEvent::Done {
+                stop_reason: Some(stop_reason.to_string()),
+                input_tokens: chunk
+                    .get("prompt_token_count")
+                    .and_then(Value::as_u64)
+                    .map(|n| n as u32),
+                output_tokens: chunk
+                    .get("generation_token_count")
+                    .and_then(Value::as_u64)
+                    .map(|n| n as u32),
+            },
+            None => StreamEvent::Ignored,
+        }
+    }
+}
+
+/// Mistral models (e.g. `mistral.mistral-7b-instruct-v0:2`)
+struct MistralAdapter;
+
+impl ModelAdapter for MistralAdapter {
+    fn build_body(&self, prompt: &str, params: &ModelParams) -> Value {
+        let wrapped_prompt = format!("<s>[INST] {} [/INST]", prompt);
+        json!({
+            "prompt": wrapped_prompt,
+            "max_tokens": params.max_tokens,
+            "temperature": params.temperature,
+            "top_p": params.top_p
+        })
+    }
+
+    fn parse_event(&mut self, chunk: &Value) -> StreamEvent {
+        let Some(output) = chunk.get("outputs").and_then(|outputs| outputs.get(0)) else {
+            return StreamEvent::Ignored;
+        };
+
+        if let Some(text) = output.get("text").and_then(Value::as_str) {
+            if !text.is_empty() {
+                return StreamEvent::Delta(text.to_string());
+            }
+        }
+        match output.get("stop_reason").and_then(Value::as_str) {
+            Some(stop_reason) => StreamEvent::Done {
+                stop_reason: Some(stop_reason.to_string()),
+                input_tokens: None,
+                output_tokens: None,
+            },
+            None => StreamEvent::Ignored,
+        }
+    }
+}
+
+/// Pick the adapter matching the requested `model_id`, defaulting to the Claude text-completion
+/// format for anything we don't recognize.
+fn select_adapter(model_id: &str) -> Box<dyn ModelAdapter> {
+    if model_id.starts_with("anthropic.claude-3") {
+        Box::new(ClaudeMessagesAdapter::default())
+    } else if model_id.starts_with("meta.llama3") {
+        Box::new(Llama3Adapter)
+    } else if model_id.starts_with("mistral.") {
+        Box::new(MistralAdapter)
+    } else {
+        Box::new(ClaudeTextAdapter)
+    }
 }
 
 /// Main Lambda handler here...
@@ -49,6 +371,8 @@ async fn function_handler(
         .await;
 
     let bedrock_client = BedrockClient::new(&config);
+    let agent_client = BedrockAgentRuntimeClient::new(&config);
+    let dynamodb_client = DynamoDbClient::new(&config);
 
     let connection_id = event
         .payload
@@ -81,6 +405,8 @@ async fn function_handler(
             let request_body = event.payload.body;
             handle_default(
                 bedrock_client,
+                agent_client,
+                dynamodb_client,
                 api_gateway_client,
                 connection_id,
                 request_body,
@@ -112,9 +438,71 @@ async fn handle_disconnect(connection_id: &str) -> Result<ApiGatewayResponse, La
     })
 }
 
+/// A `stop` command and the generation it targets arrive as separate, concurrent Lambda
+/// invocations — API Gateway won't route them to the same execution environment, so there's no
+/// process memory to share a `CancellationToken` through. Instead the stop signal is a row in
+/// DynamoDB, keyed by `"{connection_id}#{request_id}"`, that `process_bedrock_stream` /
+/// `process_agent_stream` poll for from their own invocation.
+fn stop_signals_table() -> String {
+    std::env::var("STOP_SIGNALS_TABLE")
+        .unwrap_or_else(|_| "bedrock-streamer-stop-signals".to_string())
+}
+
+fn stop_signal_key(connection_id: &str, request_id: &str) -> String {
+    format!("{}#{}", connection_id, request_id)
+}
+
+/// Record that `request_id` on this connection should stop. The row carries a short TTL so it's
+/// cleaned up even if the targeted generation already finished by the time this lands.
+async fn request_stop(
+    dynamodb_client: &DynamoDbClient,
+    connection_id: &str,
+    request_id: &str,
+) -> Result<(), LambdaError> {
+    let ttl = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        + 300;
+
+    dynamodb_client
+        .put_item()
+        .table_name(stop_signals_table())
+        .item(
+            "requestKey",
+            AttributeValue::S(stop_signal_key(connection_id, request_id)),
+        )
+        .item("ttl", AttributeValue::N(ttl.to_string()))
+        .send()
+        .await?;
+
+    Ok(())
+}
+
+/// Whether a stop has been requested for `request_id` on this connection.
+async fn is_stop_requested(
+    dynamodb_client: &DynamoDbClient,
+    connection_id: &str,
+    request_id: &str,
+) -> Result<bool, LambdaError> {
+    let item = dynamodb_client
+        .get_item()
+        .table_name(stop_signals_table())
+        .key(
+            "requestKey",
+            AttributeValue::S(stop_signal_key(connection_id, request_id)),
+        )
+        .send()
+        .await?;
+
+    Ok(item.item.is_some())
+}
+
 /// Handle WebSocket default message
 async fn handle_default(
     bedrock_client: BedrockClient,
+    agent_client: BedrockAgentRuntimeClient,
+    dynamodb_client: DynamoDbClient,
     api_gateway_client: ApiGatewayManagementClient,
     connection_id: &str,
     request_body: Option<String>,
@@ -122,45 +510,124 @@ async fn handle_default(
     println!("Calling $default with connection_id [{}]", connection_id);
 
     // Parse the incoming JSON payload
-    let story_request: StoryRequest = match request_body {
+    let client_request: ClientRequest = match request_body {
         Some(body_str) => serde_json::from_str(&body_str)
             .map_err(|e| LambdaError::from(format!("Failed to parse request body: {}", e)))?,
         None => return Err(LambdaError::from("Missing request body")),
     };
 
+    match client_request {
+        ClientRequest::Stop(stop_request) => {
+            handle_stop(&dynamodb_client, connection_id, stop_request).await
+        }
+        ClientRequest::Story(story_request) => {
+            handle_story(
+                bedrock_client,
+                dynamodb_client,
+                api_gateway_client,
+                connection_id,
+                story_request,
+            )
+            .await
+        }
+        ClientRequest::RagQuery(rag_request) => {
+            handle_rag_query(
+                agent_client,
+                dynamodb_client,
+                api_gateway_client,
+                connection_id,
+                rag_request,
+            )
+            .await
+        }
+    }
+}
+
+/// Request cancellation of the Bedrock/Agent task for one multiplexed request on this connection
+async fn handle_stop(
+    dynamodb_client: &DynamoDbClient,
+    connection_id: &str,
+    stop_request: StopRequest,
+) -> Result<ApiGatewayResponse, LambdaError> {
+    if stop_request.action != "stop" {
+        return Err(LambdaError::from(format!(
+            "Unsupported action [{}] for $default (stop)",
+            stop_request.action
+        )));
+    }
+
+    println!(
+        "Stopping request [{}] on connection [{}]",
+        stop_request.request_id, connection_id
+    );
+    request_stop(dynamodb_client, connection_id, &stop_request.request_id).await?;
+
+    Ok(ApiGatewayResponse {
+        status_code: 200,
+        body: "Message processed...: $default (stop)".to_string(),
+    })
+}
+
+/// Generate a short story from a free-form prompt
+async fn handle_story(
+    bedrock_client: BedrockClient,
+    dynamodb_client: DynamoDbClient,
+    api_gateway_client: ApiGatewayManagementClient,
+    connection_id: &str,
+    story_request: StoryRequest,
+) -> Result<ApiGatewayResponse, LambdaError> {
     // Construct the prompt based on the type of story to create
     let prompt = format!(
-        "\n\nHuman: Tell me a very short story about: {}\n\nAssistant:",
+        "Tell me a very short story about: {}",
         story_request.story_type
     );
     println!("Bedrock story prompt...: {}", prompt);
 
-    // Create the Bedrock payload
-    let payload = json!({
-        "prompt": prompt,
-        "max_tokens_to_sample": 300,
-        "temperature": 0.7,
-        "top_k": 250,
-        "top_p": 1,
-        "stop_sequences": ["\n\nHuman:"]
-    });
+    // Select the adapter for the requested model and build its request body
+    let adapter = select_adapter(&story_request.model_id);
+    let params = ModelParams::default();
+    let payload = adapter.build_body(&prompt, &params);
     let body = BedrockBlob::new(serde_json::to_string(&payload)?);
 
+    // GenAI span following the OpenTelemetry GenAI semantic conventions
+    let span = tracing::info_span!(
+        "gen_ai.invoke_model",
+        "gen_ai.system" = "bedrock",
+        "gen_ai.request.model" = %story_request.model_id,
+        "gen_ai.request.temperature" = params.temperature,
+        "gen_ai.request.max_tokens" = params.max_tokens,
+        "gen_ai.usage.input_tokens" = tracing::field::Empty,
+        "gen_ai.usage.output_tokens" = tracing::field::Empty,
+        "gen_ai.response.finish_reasons" = tracing::field::Empty,
+    );
+    let started_at = Instant::now();
+
     // Make the Bedrock request
     let bedrock_response = bedrock_client
         .invoke_model_with_response_stream()
-        .model_id("anthropic.claude-v2")
+        .model_id(&story_request.model_id)
         .content_type("application/json")
         .accept("application/json")
         .body(body)
         .send()
+        .instrument(span.clone())
         .await?;
 
+    gen_ai_metrics().invocations.add(
+        1,
+        &[KeyValue::new("gen_ai.request.model", story_request.model_id.clone())],
+    );
+
     // Start reading from Bedrock & writing the API GW
     bedrock_websocket_pipeline(
         bedrock_response,
         api_gateway_client,
+        dynamodb_client,
         connection_id.to_string(),
+        story_request.request_id,
+        adapter,
+        span,
+        started_at,
     )
     .await?;
 
@@ -170,18 +637,231 @@ async fn handle_default(
     })
 }
 
+/// Answer a retrieval-augmented query via a Bedrock Agent / Knowledge Base
+async fn handle_rag_query(
+    agent_client: BedrockAgentRuntimeClient,
+    dynamodb_client: DynamoDbClient,
+    api_gateway_client: ApiGatewayManagementClient,
+    connection_id: &str,
+    rag_request: RagRequest,
+) -> Result<ApiGatewayResponse, LambdaError> {
+    println!(
+        "Invoking Bedrock Agent [{}] for connection_id [{}]",
+        rag_request.agent_id, connection_id
+    );
+
+    // Scope the session id to this request — a connection can have several RagQuery requests in
+    // flight at once, and a Bedrock Agent session isn't meant to be driven concurrently
+    let session_id = format!("{}#{}", connection_id, rag_request.request_id);
+    let agent_response = agent_client
+        .invoke_agent()
+        .agent_id(&rag_request.agent_id)
+        .agent_alias_id(&rag_request.agent_alias_id)
+        .session_id(session_id)
+        .input_text(&rag_request.query)
+        .send()
+        .await?;
+
+    // Start reading from the Agent & writing the API GW
+    agent_websocket_pipeline(
+        agent_response,
+        api_gateway_client,
+        dynamodb_client,
+        connection_id.to_string(),
+        rag_request.request_id,
+    )
+    .await?;
+
+    Ok(ApiGatewayResponse {
+        status_code: 200,
+        body: "Message processed...: $default (rag)".to_string(),
+    })
+}
+
+/// Start the Agent + Websocket threads
+async fn agent_websocket_pipeline(
+    response: InvokeAgentOutput,
+    api_gateway_client: ApiGatewayManagementClient,
+    dynamodb_client: DynamoDbClient,
+    connection_id: String,
+    request_id: String,
+) -> Result<(), LambdaError> {
+    let (sender, receiver) = mpsc::channel(100); // Adjust buffer size as needed
+    let cancellation = CancellationToken::new();
+    let websocket_cancellation = cancellation.clone();
+    let agent_connection_id = connection_id.clone();
+
+    let agent_task = tokio::spawn(async move {
+        process_agent_stream(
+            sender,
+            response,
+            cancellation,
+            dynamodb_client,
+            agent_connection_id,
+            request_id,
+        )
+        .await
+    });
+
+    let websocket_task = tokio::spawn(async move {
+        send_to_websocket(
+            receiver,
+            api_gateway_client,
+            connection_id,
+            websocket_cancellation,
+        )
+        .await
+    });
+
+    // Wait for both tasks to complete
+    let (agent_result, websocket_result) = tokio::try_join!(agent_task, websocket_task)
+        .map_err(|e| LambdaError::from(format!("Task join error: {}", e)))?;
+
+    // Propagate errors from the tasks
+    agent_result?;
+    websocket_result?;
+
+    Ok(())
+}
+
+/// Process the Bedrock Agent response stream, forwarding completion chunks and citations
+async fn process_agent_stream(
+    sender: mpsc::Sender<OutboundFrame>,
+    mut agent_response: InvokeAgentOutput,
+    cancellation: CancellationToken,
+    dynamodb_client: DynamoDbClient,
+    connection_id: String,
+    request_id: String,
+) -> Result<(), LambdaError> {
+    println!("Processing Bedrock Agent stream...");
+    let mut stop_poll = tokio::time::interval(Duration::from_secs(1));
+
+    loop {
+        let event = tokio::select! {
+            _ = cancellation.cancelled() => {
+                println!("Client disconnected, stopping Agent stream");
+                break;
+            }
+            _ = stop_poll.tick() => {
+                if is_stop_requested(&dynamodb_client, &connection_id, &request_id).await? {
+                    println!("Stop requested for request [{}], stopping Agent stream", request_id);
+                    cancellation.cancel();
+                    break;
+                }
+                continue;
+            }
+            received = agent_response.completion.recv() => match received.map_err(LambdaError::from)? {
+                Some(event) => event,
+                None => break,
+            },
+        };
+
+        match event {
+            AgentResponseStream::Chunk(chunk) => {
+                if let Some(blob) = chunk.bytes() {
+                    let completion = String::from_utf8_lossy(blob.as_ref()).to_string();
+                    if sender
+                        .send(OutboundFrame::Completion {
+                            request_id: request_id.clone(),
+                            completion,
+                        })
+                        .await
+                        .is_err()
+                    {
+                        if cancellation.is_cancelled() {
+                            println!("Client disconnected, stopping Agent stream");
+                            break;
+                        }
+                        return Err(LambdaError::from(
+                            "Receiver dropped, stopping Agent processing",
+                        ));
+                    }
+                }
+
+                let citations: Vec<Citation> = chunk
+                    .attribution()
+                    .map(|attribution| attribution.citations())
+                    .unwrap_or_default()
+                    .iter()
+                    .flat_map(|citation| citation.retrieved_references())
+                    .map(|reference| Citation {
+                        content: reference.content().and_then(|c| c.text()).map(str::to_string),
+                        location: reference
+                            .location()
+                            .and_then(|location| location.s3_location())
+                            .and_then(|s3| s3.uri())
+                            .map(str::to_string),
+                    })
+                    .collect();
+
+                if !citations.is_empty()
+                    && sender
+                        .send(OutboundFrame::Citations {
+                            request_id: request_id.clone(),
+                            citations,
+                        })
+                        .await
+                        .is_err()
+                {
+                    if cancellation.is_cancelled() {
+                        println!("Client disconnected, stopping Agent stream");
+                        break;
+                    }
+                    return Err(LambdaError::from(
+                        "Receiver dropped, stopping Agent processing",
+                    ));
+                }
+            }
+            other => {
+                eprintln!("Unexpected agent event: {:?}", other);
+            }
+        }
+    }
+
+    println!("Agent stream processing complete...");
+    Ok(())
+}
+
 /// Start the Bedrock + Websocket threads
 async fn bedrock_websocket_pipeline(
     response: InvokeModelWithResponseStreamOutput,
     api_gateway_client: ApiGatewayManagementClient,
+    dynamodb_client: DynamoDbClient,
     connection_id: String,
+    request_id: String,
+    adapter: Box<dyn ModelAdapter>,
+    span: tracing::Span,
+    started_at: Instant,
 ) -> Result<(), LambdaError> {
     let (sender, receiver) = mpsc::channel(100); // Adjust buffer size as needed
+    let cancellation = CancellationToken::new();
+    let websocket_cancellation = cancellation.clone();
+    let bedrock_connection_id = connection_id.clone();
 
-    let bedrock_task = tokio::spawn(async move { process_bedrock_stream(sender, response).await });
+    let bedrock_task = tokio::spawn(
+        async move {
+            process_bedrock_stream(
+                sender,
+                response,
+                adapter,
+                cancellation,
+                dynamodb_client,
+                bedrock_connection_id,
+                request_id,
+            )
+            .await
+        }
+        .instrument(span.clone()),
+    );
 
     let websocket_task = tokio::spawn(async move {
-        send_to_websocket(receiver, api_gateway_client, connection_id).await
+        send_to_websocket(
+            receiver,
+            api_gateway_client,
+            connection_id,
+            websocket_cancellation,
+        )
+        .await
     });
 
     // Wait for both tasks to complete
@@ -189,38 +869,113 @@ async fn bedrock_websocket_pipeline(
         .map_err(|e| LambdaError::from(format!("Task join error: {}", e)))?;
 
     // Propagate errors from the tasks
-    bedrock_result?;
+    let summary = bedrock_result?;
     websocket_result?;
+    println!("Generation finished: {:?}", summary);
+
+    span.record("gen_ai.usage.input_tokens", summary.input_tokens);
+    span.record("gen_ai.usage.output_tokens", summary.output_tokens);
+    span.record(
+        "gen_ai.response.finish_reasons",
+        summary.stop_reason.as_deref(),
+    );
+    gen_ai_metrics().operation_duration.record(
+        started_at.elapsed().as_secs_f64(),
+        &[KeyValue::new(
+            "gen_ai.response.finish_reason",
+            summary.stop_reason.unwrap_or_default(),
+        )],
+    );
 
     Ok(())
 }
 
+/// Final state of a completed generation, surfaced once the Bedrock stream ends
+#[derive(Debug, Default)]
+struct StreamSummary {
+    stop_reason: Option<String>,
+    input_tokens: Option<u32>,
+    output_tokens: Option<u32>,
+}
+
 /// Process the Bedrock stream
 async fn process_bedrock_stream(
-    sender: mpsc::Sender<BedrockResponse>,
+    sender: mpsc::Sender<OutboundFrame>,
     mut bedrock_response: InvokeModelWithResponseStreamOutput,
-) -> Result<(), LambdaError> {
+    mut adapter: Box<dyn ModelAdapter>,
+    cancellation: CancellationToken,
+    dynamodb_client: DynamoDbClient,
+    connection_id: String,
+    request_id: String,
+) -> Result<StreamSummary, LambdaError> {
     println!("Processing Bedrock stream...");
+    let mut summary = StreamSummary::default();
+    let started_at = Instant::now();
+    let mut first_token_recorded = false;
+    let mut stop_poll = tokio::time::interval(Duration::from_secs(1));
+
+    loop {
+        let event = tokio::select! {
+            _ = cancellation.cancelled() => {
+                println!("Client disconnected, stopping Bedrock stream");
+                break;
+            }
+            _ = stop_poll.tick() => {
+                if is_stop_requested(&dynamodb_client, &connection_id, &request_id).await? {
+                    println!("Stop requested for request [{}], stopping Bedrock stream", request_id);
+                    cancellation.cancel();
+                    break;
+                }
+                continue;
+            }
+            received = bedrock_response.body.recv() => match received.map_err(LambdaError::from)? {
+                Some(event) => event,
+                None => break,
+            },
+        };
 
-    while let Some(event) = bedrock_response
-        .body
-        .recv()
-        .await
-        .map_err(LambdaError::from)?
-    {
         match event {
             ResponseStream::Chunk(payload) => {
                 if let Some(blob) = payload.bytes() {
                     let data = BedrockBlob::clone(blob).into_inner();
-                    match serde_json::from_slice::<BedrockResponse>(&data) {
-                        Ok(response) => {
-                            if sender.send(response).await.is_err() {
-                                eprintln!("Receiver dropped error");
-                                return Err(LambdaError::from(
-                                    "Receiver dropped, stopping Bedrock processing",
-                                ));
+                    match serde_json::from_slice::<Value>(&data) {
+                        Ok(chunk) => match adapter.parse_event(&chunk) {
+                            StreamEvent::Delta(text) => {
+                                if !first_token_recorded {
+                                    first_token_recorded = true;
+                                    gen_ai_metrics()
+                                        .time_to_first_token
+                                        .record(started_at.elapsed().as_secs_f64(), &[]);
+                                }
+                                if sender
+                                    .send(OutboundFrame::Completion {
+                                        request_id: request_id.clone(),
+                                        completion: text,
+                                    })
+                                    .await
+                                    .is_err()
+                                {
+                                    if cancellation.is_cancelled() {
+                                        println!("Client disconnected, stopping Bedrock stream");
+                                        break;
+                                    }
+                                    eprintln!("Receiver dropped error");
+                                    return Err(LambdaError::from(
+                                        "Receiver dropped, stopping Bedrock processing",
+                                    ));
+                                }
                             }
-                        }
+                            StreamEvent::Done {
+                                stop_reason,
+                                input_tokens,
+                                output_tokens,
+                            } => {
+                                summary.stop_reason = stop_reason;
+                                summary.input_tokens = input_tokens;
+                                summary.output_tokens = output_tokens;
+                            }
+                            StreamEvent::Ignored => {}
+                        },
                         Err(e) => {
                             eprintln!("Error deserializing response: {:?}", e);
                             return Err(LambdaError::from(e));
@@ -236,40 +991,119 @@ async fn process_bedrock_stream(
     }
 
     println!("Bedrock stream processing complete...");
-    Ok(())
+    Ok(summary)
+}
+
+/// True if a `post_to_connection` failure means the client already disconnected (HTTP 410 Gone),
+/// as opposed to a real service error.
+fn is_gone_error<R>(err: &SdkError<PostToConnectionError, R>) -> bool {
+    err.as_service_error()
+        .is_some_and(PostToConnectionError::is_gone_exception)
 }
 
 /// Process incoming Bedrock messages and send to WebSocket
 async fn send_to_websocket(
-    mut reciever: mpsc::Receiver<BedrockResponse>,
+    mut reciever: mpsc::Receiver<OutboundFrame>,
     api_gateway_client: ApiGatewayManagementClient,
     connection_id: String,
+    cancellation: CancellationToken,
 ) -> Result<(), LambdaError> {
     println!("Starting WebSocket sender...");
 
-    while let Some(response) = reciever.recv().await {
-        api_gateway_client
+    while let Some(frame) = reciever.recv().await {
+        let result = api_gateway_client
             .post_to_connection()
             .connection_id(connection_id.clone())
             .data(ApiGatewayBlob::new(
-                serde_json::to_vec(&response).map_err(|e| LambdaError::from(e.to_string()))?,
+                serde_json::to_vec(&frame).map_err(|e| LambdaError::from(e.to_string()))?,
             ))
             .send()
-            .await
-            .map_err(LambdaError::from)?;
+            .await;
+
+        if let Err(err) = result {
+            if is_gone_error(&err) {
+                println!(
+                    "Connection {} is gone, treating as clean shutdown",
+                    connection_id
+                );
+                cancellation.cancel();
+                return Ok(());
+            }
+            return Err(LambdaError::from(err));
+        }
     }
 
     println!("WebSocket sender complete...");
     Ok(())
 }
 
+/// Install the OTLP trace/metric exporters if `OTEL_EXPORTER_OTLP_ENDPOINT` is set; otherwise
+/// telemetry stays local (stdout logs only).
+fn init_telemetry() -> Option<tracing_opentelemetry::OpenTelemetryLayer<tracing_subscriber::Registry, opentelemetry_sdk::trace::Tracer>>
+{
+    let endpoint = std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok()?;
+
+    let tracer_provider = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(&endpoint),
+        )
+        .install_batch(opentelemetry_sdk::runtime::Tokio)
+        .expect("failed to install OTLP tracer");
+
+    let meter_provider = opentelemetry_otlp::new_pipeline()
+        .metrics(opentelemetry_sdk::runtime::Tokio)
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(&endpoint),
+        )
+        .build()
+        .expect("failed to install OTLP meter");
+
+    global::set_meter_provider(meter_provider);
+    Some(tracing_opentelemetry::layer().with_tracer(tracer_provider.tracer("bedrock-streamer")))
+}
+
+/// GenAI client metrics, following the OpenTelemetry GenAI semantic conventions
+struct GenAiMetrics {
+    invocations: Counter<u64>,
+    operation_duration: Histogram<f64>,
+    time_to_first_token: Histogram<f64>,
+}
+
+static GEN_AI_METRICS: OnceLock<GenAiMetrics> = OnceLock::new();
+
+fn gen_ai_metrics() -> &'static GenAiMetrics {
+    GEN_AI_METRICS.get_or_init(|| {
+        let meter = global::meter("bedrock-streamer");
+        GenAiMetrics {
+            invocations: meter.u64_counter("gen_ai.client.invocations").build(),
+            operation_duration: meter
+                .f64_histogram("gen_ai.client.operation.duration")
+                .build(),
+            time_to_first_token: meter
+                .f64_histogram("gen_ai.server.time_to_first_token")
+                .build(),
+        }
+    })
+}
+
 /// Lambda Entry
 #[tokio::main]
 async fn main() -> Result<(), LambdaError> {
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::INFO)
+    let otel_layer = init_telemetry();
+
+    let fmt_layer = tracing_subscriber::fmt::layer()
         .with_target(false)
         .without_time()
+        .with_filter(tracing_subscriber::filter::LevelFilter::INFO);
+
+    tracing_subscriber::registry()
+        .with(fmt_layer)
+        .with(otel_layer)
         .init();
 
     lambda_runtime::run(service_fn(function_handler)).await